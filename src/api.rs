@@ -0,0 +1,287 @@
+use crate::common::{DownloadError, Downloader};
+use log::{debug, trace};
+use reqwest::{Method, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ApiError {
+    InvalidUrl(String),
+    /// The requested path does not exist.
+    NotFound(String),
+    /// Any other unsuccessful response, with the server's message or body.
+    Http(StatusCode, String),
+    /// The server answered with a non-zero status in the response body.
+    Api(i32, String),
+    /// The response was not the expected JSON, holds the error and the raw response.
+    Parse(serde_json::Error, String),
+    Network(DownloadError),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::InvalidUrl(url) => write!(f, "Invalid URL: {}", url),
+            ApiError::NotFound(url) => write!(f, "Not found: {}", url),
+            ApiError::Http(status, message) => write!(f, "Server responded with status {}: {}", status, message),
+            ApiError::Api(status, message) => write!(f, "Server reported error {}: {}", status, message),
+            ApiError::Parse(e, raw) => write!(f, "Failed to parse response: {}\nRaw response: {}", e, raw),
+            ApiError::Network(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ApiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ApiError::Parse(e, _) => Some(e),
+            ApiError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DownloadError> for ApiError {
+    fn from(err: DownloadError) -> Self {
+        ApiError::Network(err)
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(err: reqwest::Error) -> Self {
+        ApiError::Network(err.into())
+    }
+}
+
+/// Envelope of every armory API response.
+#[derive(Deserialize, Debug)]
+struct ApiResponse<T> {
+    status: i32,
+    message: String,
+    data: Option<T>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactKind {
+    #[default]
+    File,
+    #[serde(alias = "dir")]
+    Directory,
+}
+
+/// A file or directory stored in a repository.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Artifact {
+    pub name: String,
+    /// Path of the artifact below the repository URL.
+    pub path: String,
+    #[serde(default, rename = "type")]
+    pub kind: ArtifactKind,
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+    /// Download URL, derived from the repository URL and `path` when the server does not send it.
+    #[serde(default)]
+    pub url: String,
+}
+
+/// A file found by `Downloader::list_tree`.
+#[derive(Debug, Clone)]
+pub struct TreeFile {
+    /// `/`-separated path of the file below the listed directory.
+    pub relative_path: String,
+    pub artifact: Artifact,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchData {
+    items: Vec<Artifact>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ListData {
+    items: Vec<Artifact>,
+    /// Number of entries in the directory across all pages.
+    #[serde(default)]
+    total: Option<usize>,
+}
+
+// Entries requested per page of a directory listing
+const LIST_PAGE_SIZE: usize = 100;
+
+fn with_download_url(mut artifact: Artifact, repo_url: &str) -> Artifact {
+    if artifact.url.is_empty() {
+        artifact.url = join_path(repo_url, &artifact.path).unwrap_or_default();
+    }
+    artifact
+}
+
+/// Appends the `/`-separated `path` to `base`, percent-encoding each segment.
+fn join_path(base: &str, path: &str) -> Option<String> {
+    let mut url = Url::parse(base).ok()?;
+    url.path_segments_mut()
+        .ok()?
+        .pop_if_empty()
+        .extend(path.split('/').filter(|segment| !segment.is_empty()));
+    Some(url.to_string())
+}
+
+/// Path of `url` below the repository at `repo_url`, decoded and starting with `/`.
+fn repo_path(repo_url: &str, url: &str) -> Result<String, ApiError> {
+    let repo = Url::parse(repo_url).map_err(|_| ApiError::InvalidUrl(repo_url.to_string()))?;
+    let target = Url::parse(url).map_err(|_| ApiError::InvalidUrl(url.to_string()))?;
+    let path = target
+        .path()
+        .strip_prefix(repo.path().trim_end_matches('/'))
+        .ok_or_else(|| ApiError::InvalidUrl(url.to_string()))?;
+    let path = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+    Ok(format!("/{}", path.trim_matches('/')))
+}
+
+impl Downloader {
+    /// GETs `url` and returns the `data` of the armory response envelope.
+    async fn get_api<T: DeserializeOwned>(&self, token: &str, url: &Url) -> Result<T, ApiError> {
+        let response: ApiResponse<T> = self.call_api(Method::GET, token, url).await?;
+        match response.data {
+            Some(data) => Ok(data),
+            None => Err(ApiError::Api(response.status, response.message)),
+        }
+    }
+
+    /// Sends an API request and returns the armory response envelope, failing
+    /// unless both the HTTP status and the envelope report success.
+    async fn call_api<T: DeserializeOwned>(&self, method: Method, token: &str, url: &Url) -> Result<ApiResponse<T>, ApiError> {
+        debug!("{} {}", method, url);
+        let response = self
+            .send_with_retries("Request", || self.authorized(method.clone(), url.as_str(), token))
+            .await?;
+
+        let status = response.status();
+        let raw = response.text().await?;
+        trace!("Raw response ({}): {}", status, raw);
+
+        if status == StatusCode::NOT_FOUND {
+            return Err(ApiError::NotFound(url.to_string()));
+        }
+        if !status.is_success() {
+            // Prefer the message of a JSON error body
+            let message = serde_json::from_str::<ApiResponse<serde_json::Value>>(&raw)
+                .map(|response| response.message)
+                .unwrap_or(raw);
+            return Err(ApiError::Http(status, message));
+        }
+
+        let response: ApiResponse<T> = match serde_json::from_str(&raw) {
+            Ok(response) => response,
+            Err(e) => return Err(ApiError::Parse(e, raw)),
+        };
+        if response.status != 0 {
+            return Err(ApiError::Api(response.status, response.message));
+        }
+        Ok(response)
+    }
+
+    /// Searches the repository at `repo_url` for artifacts matching `query`,
+    /// returning at most `limit` of them.
+    pub async fn search(&self, token: &str, repo_url: &str, query: &str, limit: usize) -> Result<Vec<Artifact>, ApiError> {
+        let mut url = Url::parse(&format!("{}/api/v1/search", repo_url.trim_end_matches('/')))
+            .map_err(|_| ApiError::InvalidUrl(repo_url.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("q", query)
+            .append_pair("limit", &limit.to_string());
+
+        let data: SearchData = self.get_api(token, &url).await?;
+        Ok(data
+            .items
+            .into_iter()
+            .take(limit)
+            .map(|artifact| with_download_url(artifact, repo_url))
+            .collect())
+    }
+
+    /// Lists the directory at `dir_url`, a URL below the repository at `repo_url`,
+    /// fetching every page of the listing.
+    pub async fn list(&self, token: &str, repo_url: &str, dir_url: &str) -> Result<Vec<Artifact>, ApiError> {
+        let path = repo_path(repo_url, dir_url)?;
+
+        let mut entries = Vec::new();
+        for page in 1.. {
+            let mut url = Url::parse(&format!("{}/api/v1/list", repo_url.trim_end_matches('/')))
+                .map_err(|_| ApiError::InvalidUrl(repo_url.to_string()))?;
+            url.query_pairs_mut()
+                .append_pair("path", &path)
+                .append_pair("page", &page.to_string())
+                .append_pair("pageSize", &LIST_PAGE_SIZE.to_string());
+
+            let data: ListData = match self.get_api(token, &url).await {
+                Ok(data) => data,
+                Err(ApiError::NotFound(_)) => return Err(ApiError::NotFound(dir_url.to_string())),
+                Err(e) => return Err(e),
+            };
+
+            let received = data.items.len();
+            entries.extend(data.items.into_iter().map(|artifact| with_download_url(artifact, repo_url)));
+
+            // Without a total, a short page is the last one
+            let done = match data.total {
+                Some(total) => entries.len() >= total,
+                None => received < LIST_PAGE_SIZE,
+            };
+            if done || received == 0 {
+                break;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Deletes the artifact at `url`, a URL below the repository at `repo_url`,
+    /// and returns the message of the server.
+    pub async fn delete(&self, token: &str, repo_url: &str, url: &str) -> Result<String, ApiError> {
+        let path = repo_path(repo_url, url)?;
+        let mut api_url = Url::parse(&format!("{}/api/v1/delete", repo_url.trim_end_matches('/')))
+            .map_err(|_| ApiError::InvalidUrl(repo_url.to_string()))?;
+        api_url.query_pairs_mut().append_pair("path", &path);
+
+        match self.call_api::<serde_json::Value>(Method::DELETE, token, &api_url).await {
+            Ok(response) => Ok(response.message),
+            Err(ApiError::NotFound(_)) => Err(ApiError::NotFound(url.to_string())),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Lists every file below the directory at `dir_url`, descending at most
+    /// `max_depth` levels of subdirectories if given.
+    pub async fn list_tree(
+        &self,
+        token: &str,
+        repo_url: &str,
+        dir_url: &str,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<TreeFile>, ApiError> {
+        let mut files = Vec::new();
+        // Directories still to list, with their path below `dir_url` and depth
+        let mut pending = vec![(dir_url.to_string(), String::new(), 0)];
+
+        while let Some((url, prefix, depth)) = pending.pop() {
+            for artifact in self.list(token, repo_url, &url).await? {
+                let relative_path = format!("{}{}", prefix, artifact.name);
+                match artifact.kind {
+                    ArtifactKind::File => files.push(TreeFile { relative_path, artifact }),
+                    ArtifactKind::Directory if max_depth.is_none_or(|max| depth < max) => {
+                        pending.push((artifact.url.clone(), format!("{}/", relative_path), depth + 1));
+                    }
+                    ArtifactKind::Directory => debug!("Not descending into {} beyond the depth limit", relative_path),
+                }
+            }
+        }
+
+        files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+        Ok(files)
+    }
+}
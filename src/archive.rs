@@ -0,0 +1,172 @@
+use crate::common::{ProgressDisplay, ProgressMode};
+use flate2::read::GzDecoder;
+use log::debug;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// Neither the file name nor the contents match a supported format.
+    Unsupported(PathBuf),
+    /// An entry would be written outside the target directory, holds its name.
+    UnsafePath(String),
+    IoError(io::Error),
+    ZipError(zip::result::ZipError),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Unsupported(path) => {
+                write!(f, "{} is not a supported archive (.tar, .tar.gz, .tgz or .zip)", path.display())
+            }
+            ArchiveError::UnsafePath(name) => write!(f, "Archive entry {} points outside the target directory", name),
+            ArchiveError::IoError(e) => write!(f, "Failed to extract archive: {}", e),
+            ArchiveError::ZipError(e) => write!(f, "Invalid zip archive: {}", e),
+        }
+    }
+}
+
+impl Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ArchiveError::IoError(e) => Some(e),
+            ArchiveError::ZipError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(err: io::Error) -> Self {
+        ArchiveError::IoError(err)
+    }
+}
+
+impl From<zip::result::ZipError> for ArchiveError {
+    fn from(err: zip::result::ZipError) -> Self {
+        ArchiveError::ZipError(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+/// Determines the format of `path` from its extension, else from its first bytes.
+pub fn detect(path: &Path) -> io::Result<Option<ArchiveKind>> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        return Ok(Some(ArchiveKind::TarGz));
+    }
+    if name.ends_with(".tar") {
+        return Ok(Some(ArchiveKind::Tar));
+    }
+    if name.ends_with(".zip") {
+        return Ok(Some(ArchiveKind::Zip));
+    }
+
+    let mut header = Vec::with_capacity(512);
+    File::open(path)?.take(512).read_to_end(&mut header)?;
+    Ok(if header.starts_with(&[0x1f, 0x8b]) {
+        Some(ArchiveKind::TarGz)
+    } else if header.starts_with(b"PK\x03\x04") {
+        Some(ArchiveKind::Zip)
+    } else if header.get(257..262) == Some(b"ustar") {
+        Some(ArchiveKind::Tar)
+    } else {
+        None
+    })
+}
+
+/// Whether `path` stays below the directory it is extracted into.
+fn is_enclosed(path: &Path) -> bool {
+    path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Reader showing the bytes read from `inner` on a progress display.
+struct ProgressReader<'a, R> {
+    inner: R,
+    display: &'a ProgressDisplay,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.display.inc(n as u64);
+        Ok(n)
+    }
+}
+
+fn extract_tar<R: Read>(reader: R, dest: &Path) -> Result<usize, ArchiveError> {
+    let mut archive = tar::Archive::new(reader);
+    let mut count = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !is_enclosed(&path) || !entry.unpack_in(dest)? {
+            return Err(ArchiveError::UnsafePath(path.display().to_string()));
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn extract_zip(file: File, dest: &Path, display: &ProgressDisplay) -> Result<usize, ArchiveError> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let Some(path) = entry.enclosed_name() else {
+            return Err(ArchiveError::UnsafePath(entry.name().to_string()));
+        };
+        let target = dest.join(path);
+
+        if entry.is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut entry, &mut File::create(&target)?)?;
+            #[cfg(unix)]
+            if let Some(mode) = entry.unix_mode() {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&target, std::fs::Permissions::from_mode(mode))?;
+            }
+        }
+        display.inc(entry.compressed_size());
+    }
+    Ok(archive.len())
+}
+
+/// Unpacks the archive at `path` into `dest`, creating it if missing, and
+/// returns the number of entries extracted. Fails without writing anything
+/// for unsupported formats, and stops at the first entry that would end up
+/// outside `dest`.
+pub fn extract(path: &Path, dest: &Path, progress: ProgressMode) -> Result<usize, ArchiveError> {
+    let kind = detect(path)?.ok_or_else(|| ArchiveError::Unsupported(path.to_path_buf()))?;
+    debug!("Extracting {} ({:?}) into {}", path.display(), kind, dest.display());
+    std::fs::create_dir_all(dest)?;
+
+    let file = File::open(path)?;
+    let label = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let display = ProgressDisplay::new(&label, progress, false);
+    display.start(file.metadata()?.len(), 0);
+
+    let count = match kind {
+        ArchiveKind::Tar => extract_tar(ProgressReader { inner: file, display: &display }, dest)?,
+        ArchiveKind::TarGz => extract_tar(GzDecoder::new(ProgressReader { inner: file, display: &display }), dest)?,
+        ArchiveKind::Zip => extract_zip(file, dest, &display)?,
+    };
+    display.finish();
+    Ok(count)
+}
@@ -0,0 +1,29 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const RED: &str = "31";
+pub const GREEN: &str = "32";
+pub const YELLOW: &str = "33";
+pub const BLUE: &str = "34";
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns coloured output off for the rest of the process, as `--no-color` does.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether output may be coloured: not disabled, and `NO_COLOR` unset or empty
+/// as described on <https://no-color.org>.
+pub fn enabled() -> bool {
+    !DISABLED.load(Ordering::Relaxed) && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// Wraps `text` in the ANSI escape codes of `color` when colours are enabled.
+pub fn paint(color: &str, text: impl Display) -> String {
+    if enabled() {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
@@ -1,7 +1,14 @@
+use crate::common::{expand_tilde, AuthScheme, TokenClaims};
+use crate::{prompt, secret};
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::error::Error;
 use std::fmt;
 
@@ -10,6 +17,8 @@ pub enum ConfigError {
     IoError(std::io::Error),
     JsonError(serde_json::Error),
     NotFound(String),
+    /// Input is needed but prompting is not allowed, holds what to supply instead.
+    InputRequired(String),
     Other(String),
 }
 
@@ -19,6 +28,7 @@ impl fmt::Display for ConfigError {
             ConfigError::IoError(e) => write!(f, "IO error: {}", e),
             ConfigError::JsonError(e) => write!(f, "JSON error: {}", e),
             ConfigError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            ConfigError::InputRequired(msg) => write!(f, "Cannot prompt for input: {}", msg),
             ConfigError::Other(msg) => write!(f, "Error: {}", msg),
         }
     }
@@ -38,62 +48,235 @@ impl From<serde_json::Error> for ConfigError {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct RepositoryConfig {
     pub url: String,
     pub username: String,
     pub password: String,
+    /// Connect timeout in seconds, overriding the built-in default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// Read timeout in seconds, overriding the built-in default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_timeout: Option<u64>,
+    /// Proxy used for every request to this repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// PEM file with an additional certificate authority for this repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Skip TLS certificate verification for this repository.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insecure: bool,
+    /// How the access token is sent, the `USER_TOKEN` cookie if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_scheme: Option<AuthScheme>,
+}
+
+/// Settings from the `global` section of the config file, applying to every repository.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GlobalConfig {
+    /// Default bandwidth limit such as `2M`, see `common::parse_rate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_rate: Option<String>,
+    /// Default size limit such as `2G`, see `common::parse_size`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<String>,
+    /// Store passwords encrypted with the master passphrase, see `secret`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypt_passwords: bool,
+    /// Where `self-update` looks for releases, an armory path or a GitHub repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_url: Option<String>,
+    /// Hosts of unconfigured repositories that `AMR_USERNAME` / `AMR_PASSWORD` may be sent to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_hosts: Vec<String>,
+}
+
+impl GlobalConfig {
+    fn is_empty(&self) -> bool {
+        self.limit_rate.is_none()
+            && self.max_size.is_none()
+            && !self.encrypt_passwords
+            && self.update_url.is_none()
+            && self.allowed_hosts.is_empty()
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Format of config files whose passwords `encrypt_passwords` encrypted.
+const ENCRYPTED_CONFIG_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 struct ConfigFile {
+    /// Format version, unset for plain files and 2 for encrypted ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
+    /// The passwords are encrypted with the master passphrase.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    encrypted: bool,
+    #[serde(default, skip_serializing_if = "GlobalConfig::is_empty")]
+    global: GlobalConfig,
     repositories: Vec<RepositoryConfig>,
 }
 
+/// A token pair in the token cache. The tokens themselves are kept in the OS
+/// keyring when it is available, and left out of `tokens.json` then.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedToken {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub access_token: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub refresh_token: String,
+    pub issued_at: u64,
+    /// Expiry read from the token if it is a JWT, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+// Cached access tokens younger than this are used without contacting the server
+const TOKEN_MAX_AGE_SECS: u64 = 30 * 60;
+
+impl CachedToken {
+    pub fn is_fresh(&self) -> bool {
+        let now = now_secs();
+        now.saturating_sub(self.issued_at) < TOKEN_MAX_AGE_SECS && self.expires_at.is_none_or(|exp| now < exp)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct TokenCacheFile {
+    tokens: BTreeMap<String, CachedToken>,
+}
+
 // pub fn check_amr_config() -> Result<bool, ConfigError> {
 //     let config_file = get_config_path()?;
 //     Ok(config_file.exists())
 // }
 
-fn get_config_path() -> Result<PathBuf, ConfigError> {
+static CONFIG_PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes every function of this module use `path` as the config file instead
+/// of `$AMR_CONFIG` or `~/.amr/config.json`. Only the first call has an effect.
+pub fn set_config_path(path: PathBuf) {
+    let _ = CONFIG_PATH_OVERRIDE.set(path);
+}
+
+/// The config file in use: the path given to `set_config_path`, else
+/// `$AMR_CONFIG`, else `~/.amr/config.json`. Cached tokens live next to it.
+pub fn get_config_path() -> Result<PathBuf, ConfigError> {
+    if let Some(path) = CONFIG_PATH_OVERRIDE.get() {
+        return Ok(path.clone());
+    }
+    // Expanded here as well, container images often set it without a shell
+    if let Some(path) = std::env::var_os("AMR_CONFIG").filter(|path| !path.is_empty()) {
+        return Ok(path.to_str().map(expand_tilde).unwrap_or_else(|| PathBuf::from(path)));
+    }
+
     let home_dir = dirs::home_dir().ok_or_else(|| ConfigError::Other("Failed to get home directory".to_string()))?;
     Ok(home_dir.join(".amr").join("config.json"))
 }
 
-fn prompt_for_repository_config(url: &str) -> Result<RepositoryConfig, ConfigError> {
-    print!("Enter username: ");
-    io::stdout().flush()?;
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn get_token_cache_path() -> Result<PathBuf, ConfigError> {
+    let config_file = get_config_path()?;
+    let config_dir = config_file.parent().ok_or_else(|| ConfigError::Other("Invalid config path".to_string()))?;
+    Ok(config_dir.join("tokens.json"))
+}
+
+fn write_private_file(path: &Path, content: &str) -> Result<(), ConfigError> {
+    let dir = path.parent().ok_or_else(|| ConfigError::Other("Invalid config path".to_string()))?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(path, content)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(path)?.permissions();
+        perms.set_mode(0o600);
+        fs::set_permissions(path, perms)?;
+    }
+
+    Ok(())
+}
+
+/// The error for credentials that would have to be prompted for, telling how to supply them instead.
+pub fn credentials_required(url: &str) -> ConfigError {
+    ConfigError::InputRequired(format!(
+        "credentials for {} are needed, pass --username and --password-file, \
+         set AMR_USERNAME and AMR_PASSWORD, or run `amr config add {}` in a terminal",
+        url, url
+    ))
+}
+
+/// Asks for a username and password on the terminal.
+pub fn prompt_for_repository_config(url: &str) -> Result<RepositoryConfig, ConfigError> {
+    if !prompt::allowed() {
+        return Err(credentials_required(url));
+    }
+
+    // Prompts go to stderr so that they show even when stdout is captured
+    eprint!("Enter username: ");
+    io::stderr().flush()?;
     let mut username = String::new();
     io::stdin().read_line(&mut username)?;
 
-    print!("Enter password: ");
-    io::stdout().flush()?;
+    eprint!("Enter password: ");
+    io::stderr().flush()?;
     let mut password = String::new();
     io::stdin().read_line(&mut password)?;
 
     Ok(RepositoryConfig {
-        url: url.trim().to_string(),
+        url: normalize_url(url),
         username: username.trim().to_string(),
         password: password.trim().to_string(),
+        ..Default::default()
     })
 }
 
-fn save_config(new_config: &RepositoryConfig) -> Result<(), ConfigError> {
+fn read_config_file() -> Result<ConfigFile, ConfigError> {
     let config_file = get_config_path()?;
-    let config_dir = config_file.parent().ok_or_else(|| ConfigError::Other("Invalid config path".to_string()))?;
 
-    fs::create_dir_all(config_dir)?;
+    if !config_file.exists() {
+        return Err(ConfigError::NotFound(format!("Config file does not exist at {}", config_file.display())));
+    }
 
-    let mut config_data = if config_file.exists() {
-        let content = fs::read_to_string(&config_file)?;
-        serde_json::from_str::<ConfigFile>(&content)?
-    } else {
-        ConfigFile { repositories: Vec::new() }
+    let content = fs::read_to_string(&config_file)?;
+    let config_data: ConfigFile = serde_json::from_str(&content)?;
+    if let Some(version) = config_data.version.filter(|version| *version > ENCRYPTED_CONFIG_VERSION) {
+        return Err(ConfigError::Other(format!(
+            "{} is in format version {}, which needs a newer amr",
+            config_file.display(),
+            version
+        )));
+    }
+    Ok(config_data)
+}
+
+fn write_config_file(config_data: &ConfigFile) -> Result<(), ConfigError> {
+    let config_file = get_config_path()?;
+    let content = serde_json::to_string_pretty(config_data)?;
+    write_private_file(&config_file, &content)
+}
+
+fn save_config(new_config: &RepositoryConfig) -> Result<(), ConfigError> {
+    let mut config_data = match read_config_file() {
+        Ok(config_data) => config_data,
+        Err(ConfigError::NotFound(_)) => ConfigFile::default(),
+        Err(e) => return Err(e),
     };
 
     let mut found = false;
     for repo in &mut config_data.repositories {
-        if repo.url == new_config.url {
+        if normalize_url(&repo.url) == normalize_url(&new_config.url) {
             *repo = new_config.clone();
             found = true;
             break;
@@ -104,42 +287,416 @@ fn save_config(new_config: &RepositoryConfig) -> Result<(), ConfigError> {
         config_data.repositories.push(new_config.clone());
     }
 
-    let content = serde_json::to_string_pretty(&config_data)?;
-    fs::write(&config_file, content)?;
+    write_config_file(&config_data)
+}
 
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&config_file)?.permissions();
-        perms.set_mode(0o600);
-        fs::set_permissions(&config_file, perms)?;
+pub fn setup_armory_configuration(url: &str, encrypt: bool) -> Result<(), ConfigError> {
+    let config = prompt_for_repository_config(url)?;
+    save_repository_credentials(&config.url, &config.username, &config.password, encrypt)?;
+    info!("Configuration saved successfully to {}", get_config_path()?.display());
+    Ok(())
+}
+
+/// Stores credentials for `url` without prompting, keeping any other settings
+/// of an existing entry for exactly that URL. The password is encrypted if
+/// `encrypt` or the `encrypt_passwords` global setting is set, if the file is
+/// encrypted, or if the existing entry's password was encrypted, and otherwise
+/// kept in the OS keyring unless that is unavailable.
+pub fn save_repository_credentials(url: &str, username: &str, password: &str, encrypt: bool) -> Result<(), ConfigError> {
+    let url = normalize_url(url);
+    let (existing, encrypt) = match read_config_file() {
+        Ok(config_data) => (
+            config_data.repositories.into_iter().find(|repo| normalize_url(&repo.url) == url),
+            encrypt || config_data.encrypted || config_data.global.encrypt_passwords,
+        ),
+        Err(ConfigError::NotFound(_)) => (None, encrypt),
+        Err(e) => return Err(e),
+    };
+
+    let encrypt = encrypt || existing.as_ref().is_some_and(|repo| secret::is_encrypted(&repo.password));
+    let password = if encrypt {
+        secret::encrypt(password)?
+    } else if secret::keyring_enabled() {
+        match secret::store_password(&url, username, password) {
+            Ok(()) => secret::KEYRING_MARKER.to_string(),
+            Err(e) => {
+                warn!("Failed to store the password in the OS keyring, saving it in the config file instead: {}", e);
+                password.to_string()
+            }
+        }
+    } else {
+        password.to_string()
+    };
+
+    save_config(&RepositoryConfig {
+        url,
+        username: username.to_string(),
+        password,
+        ..existing.unwrap_or_default()
+    })
+}
+
+fn env_var_suffix(target_url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(target_url).ok()?;
+    let host = url.host_str()?;
+    Some(host
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect())
+}
+
+/// The username and password for `target_url` set in the environment, see
+/// `load_armory_configuration`.
+pub fn credentials_from_env(target_url: &str) -> Option<(String, String)> {
+    let read_pair = |suffix: &str| {
+        let username = std::env::var(format!("AMR_USERNAME{}", suffix)).ok()?;
+        let password = std::env::var(format!("AMR_PASSWORD{}", suffix)).ok()?;
+        Some((username, password))
+    };
+
+    env_var_suffix(target_url)
+        .and_then(|host| read_pair(&format!("_{}", host)).or_else(|| read_pair(&format!("__{}", host))))
+        .or_else(|| {
+            let pair = read_pair("")?;
+            if accepts_global_credentials(target_url) {
+                return Some(pair);
+            }
+            warn!(
+                "Not sending AMR_USERNAME and AMR_PASSWORD to {}, which is neither configured nor in allowed_hosts",
+                target_url
+            );
+            None
+        })
+}
+
+/// Whether `target_url` is a configured repository or on a host listed in the
+/// `allowed_hosts` global setting. Only those are sent the credentials of
+/// `AMR_USERNAME` / `AMR_PASSWORD`, which a link to a look-alike host such as
+/// `armory.attacker.io` would otherwise collect.
+fn accepts_global_credentials(target_url: &str) -> bool {
+    if find_repository(target_url).is_ok() {
+        return true;
     }
+    let Some(host) = reqwest::Url::parse(target_url).ok().and_then(|url| url.host_str().map(String::from)) else {
+        return false;
+    };
+    load_global_config().is_ok_and(|global| global.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(&host)))
+}
 
-    Ok(())
+/// Normalizes a repository URL for comparison: lowercase scheme and host,
+/// explicit non-default port, no trailing slash, query or fragment.
+pub fn normalize_url(url: &str) -> String {
+    let url = url.trim();
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => {
+            let mut normalized = format!("{}://{}", parsed.scheme(), parsed.host_str().unwrap_or_default());
+            if let Some(port) = parsed.port() {
+                normalized.push_str(&format!(":{}", port));
+            }
+            normalized.push_str(parsed.path().trim_end_matches('/'));
+            normalized
+        }
+        Err(_) => url.trim_end_matches('/').to_string(),
+    }
 }
 
-pub fn setup_armory_configuration(url: &str) -> Result<(), ConfigError> {
-    let config = prompt_for_repository_config(url)?;
-    save_config(&config)?;
-    println!("Configuration saved successfully to ~/.amr/config.json");
-    Ok(())
+/// Index of the repository whose URL is the longest prefix of `target_url`,
+/// comparing whole path segments of the normalized URLs.
+fn best_match(repositories: &[RepositoryConfig], target_url: &str) -> Option<usize> {
+    let target = normalize_url(target_url);
+
+    repositories
+        .iter()
+        .enumerate()
+        .map(|(index, repo)| (index, normalize_url(&repo.url)))
+        .filter(|(_, url)| target == *url || target.starts_with(&format!("{}/", url)))
+        .max_by_key(|(_, url)| url.len())
+        .map(|(index, _)| index)
+}
+
+fn find_repository(target_url: &str) -> Result<RepositoryConfig, ConfigError> {
+    find_repository_entry(target_url).map(|(config, _)| config)
+}
+
+/// The entry for `target_url` and whether the file holding it is encrypted.
+fn find_repository_entry(target_url: &str) -> Result<(RepositoryConfig, bool), ConfigError> {
+    let mut config_data = read_config_file()?;
+
+    match best_match(&config_data.repositories, target_url) {
+        Some(index) => Ok((config_data.repositories.swap_remove(index), config_data.encrypted)),
+        None => Err(ConfigError::NotFound(format!("No configuration found for URL: {}", target_url))),
+    }
+}
+
+/// Returns the connection settings configured for `target_url`, without its
+/// credentials, so that nothing has to be decrypted.
+pub fn load_repository_settings(target_url: &str) -> Option<RepositoryConfig> {
+    let mut config = find_repository(target_url).ok()?;
+    config.password.clear();
+    Some(config)
 }
 
+/// Returns the normalized URL of the configured repository that `url` belongs to.
+pub fn matching_repository_url(url: &str) -> Option<String> {
+    find_repository(url).ok().map(|repo| normalize_url(&repo.url))
+}
+
+/// Looks up the credentials for `target_url`.
+///
+/// Precedence, highest first:
+/// 1. `AMR_USERNAME_<HOST>` / `AMR_PASSWORD_<HOST>`, where `<HOST>` is the
+///    repository host upper-cased with every non-alphanumeric character
+///    replaced by `_` (`armory.example.com` -> `ARMORY_EXAMPLE_COM`), also
+///    accepted with two underscores before `<HOST>`
+/// 2. `AMR_USERNAME` / `AMR_PASSWORD`, for configured repositories and hosts
+///    in the `allowed_hosts` global setting only
+/// 3. the entry in `~/.amr/config.json` whose URL is the longest prefix of `target_url`
+///
+/// Both variables of a pair must be set for it to be used. Settings other than
+/// the credentials always come from the config file entry, if there is one.
+/// Passwords kept in the OS keyring are read from there and encrypted ones
+/// (`enc:...`) are decrypted, asking for the master passphrase unless
+/// `AMR_PASSPHRASE` is set. Plaintext ones are used as they are, after moving
+/// them to the keyring if it is available and the file is not an encrypted one
+/// (`"version": 2, "encrypted": true`).
 pub fn load_armory_configuration(target_url: &str) -> Result<RepositoryConfig, ConfigError> {
-    let config_file = get_config_path()?;
+    let file_config = find_repository_entry(target_url);
 
-    if !config_file.exists() {
-        return Err(ConfigError::NotFound(format!("Config file does not exist at {}", config_file.display())));
+    if let Some((username, password)) = credentials_from_env(target_url) {
+        // Per-repository settings from the file still apply to env credentials
+        let mut config = file_config.map(|(config, _)| config).unwrap_or_else(|_| RepositoryConfig {
+            url: target_url.to_string(),
+            ..Default::default()
+        });
+        config.username = username;
+        config.password = password;
+        return Ok(config);
     }
 
-    let content = fs::read_to_string(&config_file)?;
-    let config_data: ConfigFile = serde_json::from_str(&content)?;
+    let (mut config, encrypted_file) = file_config?;
+    if secret::is_in_keyring(&config.password) {
+        config.password = secret::load_password(&normalize_url(&config.url), &config.username)?;
+    } else if secret::is_encrypted(&config.password) {
+        config.password = secret::decrypt(&config.password)?;
+    } else if encrypted_file && !config.password.is_empty() {
+        warn!("The password for {} is not encrypted, run `amr config encrypt` to encrypt it", config.url);
+    } else if !config.password.is_empty() && secret::keyring_enabled() {
+        migrate_to_keyring(&config);
+    }
+    Ok(config)
+}
 
-    for repo in config_data.repositories {
-        if repo.url == target_url {
-            return Ok(repo);
+/// Moves the plaintext password of `config` into the OS keyring.
+fn migrate_to_keyring(config: &RepositoryConfig) {
+    let url = normalize_url(&config.url);
+    if let Err(e) = secret::store_password(&url, &config.username, &config.password) {
+        debug!("Keeping the password for {} in the config file, the OS keyring failed: {}", url, e);
+        return;
+    }
+    let stored = RepositoryConfig { password: secret::KEYRING_MARKER.to_string(), ..config.clone() };
+    match save_config(&stored) {
+        Ok(()) => info!("Moved the password for {} from the config file to the OS keyring", url),
+        Err(e) => warn!("Failed to remove the password for {} from the config file: {}", url, e),
+    }
+}
+
+pub fn load_global_config() -> Result<GlobalConfig, ConfigError> {
+    match read_config_file() {
+        Ok(config_data) => Ok(config_data.global),
+        Err(ConfigError::NotFound(_)) => Ok(GlobalConfig::default()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn list_repositories() -> Result<Vec<RepositoryConfig>, ConfigError> {
+    match read_config_file() {
+        Ok(config_data) => Ok(config_data.repositories),
+        Err(ConfigError::NotFound(_)) => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Encrypts the password of every repository with the master passphrase and
+/// marks the file as encrypted, so that those added later are encrypted too.
+/// Passwords kept in the OS keyring move into the file. Returns the number of
+/// passwords encrypted.
+pub fn encrypt_passwords() -> Result<usize, ConfigError> {
+    let mut config_data = read_config_file()?;
+
+    // Catches a passphrase other than the one already in use before mixing the two
+    match config_data.repositories.iter().find(|repo| secret::is_encrypted(&repo.password)) {
+        Some(repo) => {
+            secret::decrypt(&repo.password)?;
+        }
+        None => secret::new_master_passphrase()?,
+    }
+
+    let mut count = 0;
+    let mut moved = Vec::new();
+    for repo in &mut config_data.repositories {
+        if secret::is_encrypted(&repo.password) || repo.password.is_empty() {
+            continue;
+        }
+        let password = if secret::is_in_keyring(&repo.password) {
+            moved.push((normalize_url(&repo.url), repo.username.clone()));
+            secret::load_password(&normalize_url(&repo.url), &repo.username)?
+        } else {
+            std::mem::take(&mut repo.password)
+        };
+        repo.password = secret::encrypt(&password)?;
+        count += 1;
+    }
+    config_data.version = Some(ENCRYPTED_CONFIG_VERSION);
+    config_data.encrypted = true;
+    write_config_file(&config_data)?;
+
+    for (url, username) in moved {
+        secret::delete_password(&url, &username);
+    }
+    Ok(count)
+}
+
+/// Reverses `encrypt_passwords`, leaving the passwords in plaintext and the
+/// file in the plain format. Returns the number of passwords decrypted.
+pub fn decrypt_passwords() -> Result<usize, ConfigError> {
+    let mut config_data = read_config_file()?;
+
+    let mut count = 0;
+    for repo in &mut config_data.repositories {
+        if secret::is_encrypted(&repo.password) {
+            repo.password = secret::decrypt(&repo.password)?;
+            count += 1;
         }
     }
+    config_data.version = None;
+    config_data.encrypted = false;
+    config_data.global.encrypt_passwords = false;
+    write_config_file(&config_data)?;
+    Ok(count)
+}
+
+pub fn remove_repository(target_url: &str) -> Result<RepositoryConfig, ConfigError> {
+    let mut config_data = read_config_file()?;
 
-    Err(ConfigError::NotFound(format!("No configuration found for URL: {}", target_url)))
-}
\ No newline at end of file
+    let index = best_match(&config_data.repositories, target_url)
+        .ok_or_else(|| ConfigError::NotFound(format!("No configuration found for URL: {}", target_url)))?;
+
+    let removed = config_data.repositories.remove(index);
+    write_config_file(&config_data)?;
+    if secret::is_in_keyring(&removed.password) {
+        secret::delete_password(&normalize_url(&removed.url), &removed.username);
+    }
+
+    Ok(removed)
+}
+
+pub fn mask_password(password: &str) -> String {
+    if password.is_empty() {
+        String::new()
+    } else {
+        "********".to_string()
+    }
+}
+
+fn read_token_cache() -> Result<TokenCacheFile, ConfigError> {
+    let cache_file = get_token_cache_path()?;
+
+    if !cache_file.exists() {
+        return Ok(TokenCacheFile::default());
+    }
+
+    let content = fs::read_to_string(&cache_file)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn write_token_cache(cache: &TokenCacheFile) -> Result<(), ConfigError> {
+    let cache_file = get_token_cache_path()?;
+    let content = serde_json::to_string_pretty(cache)?;
+    write_private_file(&cache_file, &content)
+}
+
+static TOKEN_CACHE_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Stops tokens from being read from or written to the cache for the rest of
+/// the process, as `--no-token-cache` does.
+pub fn disable_token_cache() {
+    TOKEN_CACHE_DISABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn token_cache_enabled() -> bool {
+    !TOKEN_CACHE_DISABLED.load(Ordering::Relaxed)
+}
+
+pub fn load_cached_token(repo_url: &str) -> Result<Option<CachedToken>, ConfigError> {
+    if !token_cache_enabled() {
+        return Ok(None);
+    }
+    let mut cache = read_token_cache()?;
+    let Some(mut token) = cache.tokens.remove(repo_url) else {
+        return Ok(None);
+    };
+
+    if token.access_token.is_empty() {
+        let Some((access_token, refresh_token)) = secret::load_tokens(repo_url) else {
+            return Ok(None);
+        };
+        token.access_token = access_token;
+        token.refresh_token = refresh_token;
+    }
+    Ok(Some(token))
+}
+
+pub fn save_cached_token(repo_url: &str, access_token: &str, refresh_token: &str) -> Result<(), ConfigError> {
+    if !token_cache_enabled() {
+        return Ok(());
+    }
+    let issued_at = now_secs();
+
+    // A corrupt cache is not worth failing over, start from scratch instead
+    let mut cache = read_token_cache().unwrap_or_default();
+    let mut token = CachedToken {
+        access_token: access_token.to_string(),
+        refresh_token: refresh_token.to_string(),
+        issued_at,
+        expires_at: TokenClaims::from_token(access_token).and_then(|claims| claims.exp),
+    };
+
+    // Only the timestamps go to the file when the keyring holds the tokens
+    match secret::store_tokens(repo_url, access_token, refresh_token) {
+        Ok(()) => {
+            token.access_token.clear();
+            token.refresh_token.clear();
+        }
+        Err(e) => debug!("Keeping the tokens for {} in the token cache file: {}", repo_url, e),
+    }
+    cache.tokens.insert(repo_url.to_string(), token);
+
+    write_token_cache(&cache)
+}
+
+pub fn remove_cached_token(repo_url: &str) -> Result<bool, ConfigError> {
+    secret::delete_tokens(repo_url);
+    let mut cache = read_token_cache()?;
+
+    if cache.tokens.remove(repo_url).is_none() {
+        return Ok(false);
+    }
+
+    write_token_cache(&cache)?;
+    Ok(true)
+}
+
+pub fn clear_token_cache() -> Result<usize, ConfigError> {
+    let cache_file = get_token_cache_path()?;
+
+    if !cache_file.exists() {
+        return Ok(0);
+    }
+
+    let cache = read_token_cache().unwrap_or_default();
+    for repo_url in cache.tokens.keys() {
+        secret::delete_tokens(repo_url);
+    }
+    fs::remove_file(&cache_file)?;
+    Ok(cache.tokens.len())
+}
@@ -0,0 +1,35 @@
+//! Library interface of `amr`, the Armory repository downloader.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! use amr::{DownloadOptions, Downloader};
+//!
+//! let downloader = Downloader::new();
+//! let token = downloader.login("https://armory.example.com", "user", "secret").await?;
+//! let outcome = downloader
+//!     .download(&token.access_token, "https://armory.example.com/files/a.tar.gz", ".", None, &DownloadOptions::default())
+//!     .await?;
+//! println!("{} ({} bytes)", outcome.path.display(), outcome.bytes_written);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Progress messages are emitted through the [`log`](https://docs.rs/log) crate
+//! at info level and below, so nothing is printed unless the application installs
+//! a logger. Progress is only shown while info logging is enabled, in the way
+//! chosen by [`DownloadOptions::progress`].
+
+pub mod api;
+pub mod archive;
+pub mod color;
+pub mod common;
+pub mod env;
+pub mod manifest;
+pub mod prompt;
+pub mod secret;
+pub mod update;
+
+pub use common::{
+    AuthError, Checksum, ClientOptions, DownloadError, DownloadOptions, DownloadOutcome, DownloadPlan, Downloader,
+    OverwritePolicy, ProgressMode, RemoteInfo, SharedProgress, UploadOptions, UploadOutcome, UserToken,
+};
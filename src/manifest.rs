@@ -0,0 +1,180 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ManifestError {
+    IoError(std::io::Error),
+    JsonError(serde_json::Error),
+    YamlError(serde_yaml::Error),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::IoError(e) => write!(f, "Failed to read manifest: {}", e),
+            ManifestError::JsonError(e) => write!(f, "Invalid JSON manifest: {}", e),
+            ManifestError::YamlError(e) => write!(f, "Invalid YAML manifest: {}", e),
+        }
+    }
+}
+
+impl Error for ManifestError {}
+
+impl From<std::io::Error> for ManifestError {
+    fn from(err: std::io::Error) -> Self {
+        ManifestError::IoError(err)
+    }
+}
+
+impl From<serde_json::Error> for ManifestError {
+    fn from(err: serde_json::Error) -> Self {
+        ManifestError::JsonError(err)
+    }
+}
+
+impl From<serde_yaml::Error> for ManifestError {
+    fn from(err: serde_yaml::Error) -> Self {
+        ManifestError::YamlError(err)
+    }
+}
+
+/// One file of a batch download.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    pub url: String,
+    /// Path to save the file under, relative to the target directory. The
+    /// name sent by the server is used if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Expected SHA-256 of the file in hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Parses a manifest: an array of entries in JSON, or in YAML unless the file
+/// name ends in `.json`.
+pub fn parse_manifest(content: &str, path: &Path) -> Result<Vec<ManifestEntry>, ManifestError> {
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+
+    if is_json {
+        Ok(serde_json::from_str(content)?)
+    } else {
+        Ok(serde_yaml::from_str(content)?)
+    }
+}
+
+pub fn read_manifest(path: &Path) -> Result<Vec<ManifestEntry>, ManifestError> {
+    let content = std::fs::read_to_string(path)?;
+    parse_manifest(&content, path)
+}
+
+/// Files of a list of URLs downloaded so far, kept in the download directory
+/// so that running the same list again after an interruption skips them.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BatchState {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Final path of each completed download by URL.
+    completed: BTreeMap<String, PathBuf>,
+}
+
+impl BatchState {
+    pub const FILE_NAME: &'static str = ".amr-batch-state.json";
+
+    /// Reads the state kept in `dir`, starting afresh if there is none or it is unreadable.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(Self::FILE_NAME);
+        let mut state = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable batch state {}: {}", path.display(), e);
+                BatchState::default()
+            }),
+            Err(_) => BatchState::default(),
+        };
+        state.path = path;
+        state
+    }
+
+    /// Where `url` was saved by an earlier run, if that file still exists.
+    pub fn completed(&self, url: &str) -> Option<&Path> {
+        self.completed.get(url).map(PathBuf::as_path).filter(|path| path.exists())
+    }
+
+    /// Records that `url` was saved to `path`, saving the state right away.
+    pub fn record(&mut self, url: &str, path: &Path) {
+        self.completed.insert(url.to_string(), path.to_path_buf());
+        let result = serde_json::to_string_pretty(self)
+            .map_err(io::Error::from)
+            .and_then(|content| std::fs::write(&self.path, content));
+        if let Err(e) = result {
+            warn!("Failed to save batch state {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Deletes the state once the whole list has been downloaded.
+    pub fn remove(&self) {
+        if let Err(e) = std::fs::remove_file(&self.path)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove batch state {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_json_manifest() {
+        let content = r#"[
+            {"url": "https://armory.example.com/a.zip", "output": "dist/a.zip", "sha256": "ab12"},
+            {"url": "https://armory.example.com/b.zip"}
+        ]"#;
+        let entries = parse_manifest(content, Path::new("files.JSON")).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].url, "https://armory.example.com/a.zip");
+        assert_eq!(entries[0].output.as_deref(), Some("dist/a.zip"));
+        assert_eq!(entries[0].sha256.as_deref(), Some("ab12"));
+        assert_eq!(entries[1].output, None);
+        assert_eq!(entries[1].sha256, None);
+    }
+
+    #[test]
+    fn parses_yaml_manifest() {
+        let content = "\
+- url: https://armory.example.com/a.zip
+  output: a.zip
+- url: https://armory.example.com/b.zip
+  sha256: cd34
+";
+        let entries = parse_manifest(content, Path::new("files.yml")).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].output.as_deref(), Some("a.zip"));
+        assert_eq!(entries[1].url, "https://armory.example.com/b.zip");
+        assert_eq!(entries[1].sha256.as_deref(), Some("cd34"));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let json = r#"[{"url": "https://armory.example.com/a.zip", "sha265": "ab12"}]"#;
+        let err = parse_manifest(json, Path::new("files.json")).unwrap_err();
+        assert!(matches!(err, ManifestError::JsonError(_)), "{}", err);
+        assert!(err.to_string().contains("sha265"), "{}", err);
+
+        let yaml = "- url: https://armory.example.com/a.zip\n  ouput: a.zip\n";
+        let err = parse_manifest(yaml, Path::new("files.yaml")).unwrap_err();
+        assert!(matches!(err, ManifestError::YamlError(_)), "{}", err);
+        assert!(err.to_string().contains("ouput"), "{}", err);
+    }
+}
@@ -0,0 +1,15 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Forbids asking the user anything for the rest of the process, as `--no-input` does.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether the user may be asked for input: not disabled, and stdin a terminal
+/// rather than a pipe or a closed descriptor that would never answer.
+pub fn allowed() -> bool {
+    !DISABLED.load(Ordering::Relaxed) && std::io::stdin().is_terminal()
+}
@@ -0,0 +1,294 @@
+use crate::env::ConfigError;
+use crate::prompt;
+use keyring::credential::CredentialPersistence;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use log::{debug, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Prefix marking an encrypted value in the config file.
+pub const ENCRYPTED_PREFIX: &str = "enc:";
+/// Stands in for a password kept in the OS keyring in the config file.
+pub const KEYRING_MARKER: &str = "keyring:";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEYRING_SERVICE: &str = "amr";
+const KEYRING_USER: &str = "master-passphrase";
+/// Keyring user of the cached tokens of a repository, next to its password.
+const TOKENS_USER: &str = "tokens";
+
+static MASTER_PASSPHRASE: OnceLock<String> = OnceLock::new();
+static KEYRING_DISABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+pub fn is_in_keyring(value: &str) -> bool {
+    value == KEYRING_MARKER
+}
+
+/// Keeps the OS keyring out of use for the rest of the process, as `--no-keyring` does.
+pub fn disable_keyring() {
+    KEYRING_DISABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn keyring_enabled() -> bool {
+    !KEYRING_DISABLED.load(Ordering::Relaxed)
+}
+
+fn keyring_entry() -> Option<keyring::Entry> {
+    if !keyring_enabled() {
+        return None;
+    }
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+        .map_err(|e| debug!("OS keyring unavailable: {}", e))
+        .ok()
+}
+
+/// The keyring entry holding the password of `username` for the repository at `url`.
+fn password_entry(url: &str, username: &str) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(&format!("{}:{}", KEYRING_SERVICE, url), username)
+}
+
+/// Stores `secret` in `entry` and reads it back, so that nothing is taken out
+/// of the config file for a store that would lose it. The kernel keyring on
+/// its own, for one, is emptied at every reboot.
+fn store_checked(entry: &keyring::Entry, secret: &str) -> keyring::Result<()> {
+    if !matches!(keyring::default::default_credential_builder().persistence(), CredentialPersistence::UntilDelete) {
+        return Err(keyring::Error::NoStorageAccess("the OS keyring does not keep secrets across reboots".into()));
+    }
+    entry.set_password(secret)?;
+    match entry.get_password()? {
+        stored if stored == secret => Ok(()),
+        _ => Err(keyring::Error::NoStorageAccess("the OS keyring returned a different secret".into())),
+    }
+}
+
+/// Stores the password of `username` for `url` in the OS keyring.
+pub fn store_password(url: &str, username: &str, password: &str) -> keyring::Result<()> {
+    store_checked(&password_entry(url, username)?, password)
+}
+
+/// Reads the password stored by `store_password`.
+pub fn load_password(url: &str, username: &str) -> Result<String, ConfigError> {
+    if !keyring_enabled() {
+        return Err(ConfigError::InputRequired(format!(
+            "the password for {} is kept in the OS keyring, which --no-keyring turns off",
+            url
+        )));
+    }
+    password_entry(url, username)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| {
+            ConfigError::Other(format!(
+                "Cannot read the password for {} from the OS keyring ({}), run `amr config add {}` to store it again",
+                url, e, url
+            ))
+        })
+}
+
+/// Removes the password stored by `store_password`, if any.
+pub fn delete_password(url: &str, username: &str) {
+    if let Ok(entry) = password_entry(url, username)
+        && let Err(e) = entry.delete_credential()
+    {
+        debug!("No password removed from the OS keyring: {}", e);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredTokens {
+    access_token: String,
+    refresh_token: String,
+}
+
+/// Stores the cached token pair of the repository at `url` in the OS keyring.
+pub fn store_tokens(url: &str, access_token: &str, refresh_token: &str) -> keyring::Result<()> {
+    if !keyring_enabled() {
+        return Err(keyring::Error::NoStorageAccess("turned off by --no-keyring".into()));
+    }
+    let tokens = StoredTokens { access_token: access_token.to_string(), refresh_token: refresh_token.to_string() };
+    let secret = serde_json::to_string(&tokens).unwrap_or_default();
+    store_checked(&password_entry(url, TOKENS_USER)?, &secret)
+}
+
+/// Reads the access and refresh token stored by `store_tokens`, if they are there.
+pub fn load_tokens(url: &str) -> Option<(String, String)> {
+    if !keyring_enabled() {
+        return None;
+    }
+    let secret = password_entry(url, TOKENS_USER)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| debug!("No cached tokens for {} in the OS keyring: {}", url, e))
+        .ok()?;
+    let tokens: StoredTokens = serde_json::from_str(&secret).ok()?;
+    Some((tokens.access_token, tokens.refresh_token))
+}
+
+/// Removes the tokens stored by `store_tokens`, if any.
+pub fn delete_tokens(url: &str) {
+    if keyring_enabled() {
+        delete_password(url, TOKENS_USER);
+    }
+}
+
+fn read_passphrase(prompt: &str) -> Result<String, ConfigError> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let mut passphrase = String::new();
+    io::stdin().read_line(&mut passphrase)?;
+    Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Asks for the master passphrase, twice with `confirm` so that a typo cannot
+/// lock away the passwords about to be encrypted.
+fn prompt_for_passphrase(confirm: bool) -> Result<String, ConfigError> {
+    if !prompt::allowed() {
+        return Err(ConfigError::InputRequired(
+            "the master passphrase is needed for encrypted passwords, set AMR_PASSPHRASE".to_string(),
+        ));
+    }
+
+    let passphrase = read_passphrase("Enter master passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(ConfigError::Other("The master passphrase must not be empty".to_string()));
+    }
+    if confirm && read_passphrase("Repeat master passphrase: ")? != passphrase {
+        return Err(ConfigError::Other("The passphrases do not match".to_string()));
+    }
+
+    if let Some(entry) = keyring_entry()
+        && let Err(e) = store_checked(&entry, &passphrase)
+    {
+        warn!("Failed to store the master passphrase in the OS keyring: {}", e);
+    }
+    Ok(passphrase)
+}
+
+/// The passphrase protecting encrypted passwords: `$AMR_PASSPHRASE` (or its
+/// older name `$AMR_MASTER_PASSPHRASE`), else the one stored in the OS
+/// keyring, else prompted for (and then stored in the keyring). It is looked
+/// up at most once per process.
+fn master_passphrase() -> Result<&'static str, ConfigError> {
+    lookup_passphrase(false)
+}
+
+/// Looks up the master passphrase before encrypting passwords with it for the
+/// first time, asking for it twice if it has to be prompted for.
+pub fn new_master_passphrase() -> Result<(), ConfigError> {
+    lookup_passphrase(true).map(|_| ())
+}
+
+fn lookup_passphrase(confirm: bool) -> Result<&'static str, ConfigError> {
+    if let Some(passphrase) = MASTER_PASSPHRASE.get() {
+        return Ok(passphrase);
+    }
+
+    let from_env = ["AMR_PASSPHRASE", "AMR_MASTER_PASSPHRASE"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|p| !p.is_empty()));
+    let passphrase = match from_env {
+        Some(passphrase) => passphrase,
+        None => match keyring_entry().and_then(|entry| entry.get_password().ok()) {
+            Some(passphrase) => passphrase,
+            None => prompt_for_passphrase(confirm)?,
+        },
+    };
+
+    Ok(MASTER_PASSPHRASE.get_or_init(|| passphrase))
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm, ConfigError> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| ConfigError::Other(format!("Failed to derive the encryption key: {}", e)))?;
+    Ok(Aes256Gcm::new(&key.into()))
+}
+
+/// Encrypts `plaintext` with the master passphrase, returning `enc:` followed
+/// by the base64 of salt, nonce and ciphertext.
+pub fn encrypt(plaintext: &str) -> Result<String, ConfigError> {
+    encrypt_with(master_passphrase()?, plaintext)
+}
+
+fn encrypt_with(passphrase: &str, plaintext: &str) -> Result<String, ConfigError> {
+    let mut rng = rand::thread_rng();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt);
+    rng.fill(&mut nonce);
+
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| ConfigError::Other("Failed to encrypt the password".to_string()))?;
+
+    let mut data = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(data)))
+}
+
+/// Reverses `encrypt`. Values without the `enc:` prefix are plaintext and
+/// returned unchanged.
+pub fn decrypt(value: &str) -> Result<String, ConfigError> {
+    if !is_encrypted(value) {
+        return Ok(value.to_string());
+    }
+    decrypt_with(master_passphrase()?, value)
+}
+
+fn decrypt_with(passphrase: &str, value: &str) -> Result<String, ConfigError> {
+    let Some(encoded) = value.strip_prefix(ENCRYPTED_PREFIX) else {
+        return Ok(value.to_string());
+    };
+
+    let invalid = || ConfigError::Other("Malformed encrypted password in the config file".to_string());
+    let data = BASE64.decode(encoded).map_err(|_| invalid())?;
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return Err(invalid());
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let plaintext = cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| ConfigError::Other("Failed to decrypt the stored password, is the master passphrase correct?".to_string()))?;
+    String::from_utf8(plaintext).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_password_round_trips() {
+        let encrypted = encrypt_with("correct horse", "s3cret pässword").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("s3cret"));
+        assert_eq!(decrypt_with("correct horse", &encrypted).unwrap(), "s3cret pässword");
+
+        // Salted, so the same password never encrypts the same way twice
+        assert_ne!(encrypt_with("correct horse", "s3cret pässword").unwrap(), encrypted);
+    }
+
+    #[test]
+    fn wrong_passphrase_is_a_clear_error() {
+        let encrypted = encrypt_with("correct horse", "s3cret").unwrap();
+        let err = decrypt_with("battery staple", &encrypted).unwrap_err();
+        assert!(err.to_string().contains("is the master passphrase correct?"), "{}", err);
+
+        let err = decrypt_with("correct horse", "enc:not base64!").unwrap_err();
+        assert!(err.to_string().contains("Malformed encrypted password"), "{}", err);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::common::{Checksum, DownloadError, DownloadOptions, Downloader, OverwritePolicy};
+use log::{debug, trace, warn};
+use reqwest::{Method, StatusCode, Url};
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Where releases are looked for unless configured otherwise.
+pub const DEFAULT_RELEASE_URL: &str = "https://github.com/wyf9661/amr";
+
+#[derive(Debug)]
+pub enum UpdateError {
+    InvalidUrl(String),
+    Http(StatusCode, String),
+    /// The release has no binary for this platform, holds the version and the expected asset name.
+    NoAsset(String, String),
+    /// The published checksum could not be read, holds its URL.
+    InvalidChecksum(String),
+    /// The response was not the expected JSON, holds the error and the raw response.
+    Parse(serde_json::Error, String),
+    Network(DownloadError),
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateError::InvalidUrl(url) => write!(f, "Invalid release URL: {}", url),
+            UpdateError::Http(status, url) => write!(f, "Server responded with status {} for {}", status, url),
+            UpdateError::NoAsset(version, asset) => write!(f, "Release {} has no {} binary", version, asset),
+            UpdateError::InvalidChecksum(url) => write!(f, "No valid SHA-256 checksum found at {}", url),
+            UpdateError::Parse(e, raw) => write!(f, "Failed to parse release information: {}\nRaw response: {}", e, raw),
+            UpdateError::Network(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for UpdateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UpdateError::Parse(e, _) => Some(e),
+            UpdateError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DownloadError> for UpdateError {
+    fn from(err: DownloadError) -> Self {
+        UpdateError::Network(err)
+    }
+}
+
+impl From<reqwest::Error> for UpdateError {
+    fn from(err: reqwest::Error) -> Self {
+        UpdateError::Network(err.into())
+    }
+}
+
+/// The newest published version and where to get its binary for this platform.
+#[derive(Debug, Clone)]
+pub struct Release {
+    pub version: String,
+    pub binary_url: String,
+    /// URL of a file holding the SHA-256 of the binary in hex, optionally
+    /// followed by the file name as written by `sha256sum`.
+    pub sha256_url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GithubRelease {
+    tag_name: String,
+    assets: Vec<GithubAsset>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Name of the release binary for the platform this was built for, e.g.
+/// `amr-linux-x86_64` or `amr-windows-x86_64.exe`.
+pub fn asset_name() -> String {
+    format!(
+        "amr-{}-{}{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+/// The GitHub API URL of the latest release if `release_url` points to a GitHub repository.
+pub fn github_api_url(release_url: &str) -> Option<String> {
+    let url = Url::parse(release_url).ok()?;
+    if url.host_str() != Some("github.com") {
+        return None;
+    }
+    let mut segments = url.path_segments()?.filter(|segment| !segment.is_empty());
+    let (owner, repo) = (segments.next()?, segments.next()?);
+    Some(format!("https://api.github.com/repos/{}/{}/releases/latest", owner, repo))
+}
+
+fn version_parts(version: &str) -> Vec<u64> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// Whether `candidate` is a higher dotted version than `current`, ignoring a leading `v`.
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    version_parts(candidate) > version_parts(current)
+}
+
+impl Downloader {
+    async fn fetch_text(&self, token: &str, url: &str) -> Result<String, UpdateError> {
+        debug!("GET {}", url);
+        let response = self
+            .send_with_retries("Request", || self.authorized(Method::GET, url, token))
+            .await?;
+        if !response.status().is_success() {
+            return Err(UpdateError::Http(response.status(), url.to_string()));
+        }
+        let raw = response.text().await?;
+        trace!("Raw response: {}", raw);
+        Ok(raw)
+    }
+
+    /// Looks up the latest release published at `release_url`: either a GitHub
+    /// repository, or an armory path holding a `latest` file with the version
+    /// and the binaries in a directory per version, each next to a `.sha256` file.
+    pub async fn latest_release(&self, token: &str, release_url: &str) -> Result<Release, UpdateError> {
+        let asset = asset_name();
+
+        if let Some(api_url) = github_api_url(release_url) {
+            let raw = self.fetch_text(token, &api_url).await?;
+            let release: GithubRelease = serde_json::from_str(&raw).map_err(|e| UpdateError::Parse(e, raw))?;
+            let version = release.tag_name.trim_start_matches('v').to_string();
+            let url_of = |name: &str| {
+                release
+                    .assets
+                    .iter()
+                    .find(|a| a.name == name)
+                    .map(|a| a.browser_download_url.clone())
+                    .ok_or_else(|| UpdateError::NoAsset(version.clone(), name.to_string()))
+            };
+            return Ok(Release {
+                binary_url: url_of(&asset)?,
+                sha256_url: url_of(&format!("{}.sha256", asset))?,
+                version,
+            });
+        }
+
+        let base = release_url.trim_end_matches('/');
+        if Url::parse(base).is_err() {
+            return Err(UpdateError::InvalidUrl(release_url.to_string()));
+        }
+        let version = self.fetch_text(token, &format!("{}/latest", base)).await?.trim().to_string();
+        if version_parts(&version).is_empty() {
+            return Err(UpdateError::InvalidUrl(format!("{}/latest", base)));
+        }
+        let binary_url = format!("{}/{}/{}", base, version, asset);
+        Ok(Release {
+            sha256_url: format!("{}.sha256", binary_url),
+            binary_url,
+            version,
+        })
+    }
+
+    /// Downloads the binary of `release`, verifies it against the published
+    /// checksum and replaces the running executable with it. The executable is
+    /// left untouched if any step fails.
+    pub async fn install_release(
+        &self,
+        token: &str,
+        release: &Release,
+        options: &DownloadOptions,
+    ) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        let checksum = self.fetch_text(token, &release.sha256_url).await?;
+        let sha256 = checksum
+            .split_whitespace()
+            .next()
+            .filter(|hex| hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| UpdateError::InvalidChecksum(release.sha256_url.clone()))?;
+
+        let exe = std::env::current_exe()?;
+        let dir = exe.parent().ok_or("Cannot determine the directory of the executable")?;
+        let dir = dir.to_str().ok_or("The directory of the executable is not valid UTF-8")?;
+        let exe_name = exe.file_name().and_then(|name| name.to_str()).unwrap_or("amr");
+        // Downloaded next to the executable so that the final rename stays on one file system
+        let new_name = format!(".{}.new", exe_name);
+
+        let options = DownloadOptions {
+            connections: 1,
+            checksums: vec![Checksum::Sha256(sha256.to_string())],
+            overwrite: OverwritePolicy::Overwrite,
+            resume: false,
+            keep_corrupt: false,
+            ..options.clone()
+        };
+        let outcome = self.download(token, &release.binary_url, dir, Some(&new_name), &options).await?;
+
+        if let Err(e) = replace_executable(&outcome.path, &exe) {
+            let _ = std::fs::remove_file(&outcome.path);
+            return Err(e.into());
+        }
+        Ok(exe)
+    }
+}
+
+/// Moves `new` over `exe`, keeping the permissions of `exe`. A running
+/// executable cannot be replaced on Windows, so it is renamed out of the way
+/// first and restored if the new one cannot be moved into place.
+fn replace_executable(new: &Path, exe: &Path) -> std::io::Result<()> {
+    let permissions = std::fs::metadata(exe)?.permissions();
+    std::fs::set_permissions(new, permissions)?;
+
+    if cfg!(windows) {
+        let old = exe.with_extension("old");
+        // Left behind by the previous update, deletable now that it is no longer running
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old)?;
+        if let Err(e) = std::fs::rename(new, exe) {
+            if let Err(e) = std::fs::rename(&old, exe) {
+                warn!("Failed to restore {}: {}", exe.display(), e);
+            }
+            return Err(e);
+        }
+        Ok(())
+    } else {
+        std::fs::rename(new, exe)
+    }
+}
@@ -0,0 +1,367 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// An empty directory of its own for `test` below the system temp directory.
+fn scratch_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("amr-cli-{}-{}", std::process::id(), test));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The `amr` binary with its configuration kept in `home` and no credentials
+/// or tokens picked up from the environment of the test run.
+fn amr(home: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_amr"));
+    for (name, _) in std::env::vars_os() {
+        if name.to_string_lossy().starts_with("AMR_") {
+            command.env_remove(name);
+        }
+    }
+    command
+        .env("HOME", home)
+        .env("AMR_CONFIG", home.join("config.json"))
+        .arg("--no-keyring");
+    command
+}
+
+/// A plain HTTP/1.1 response that closes the connection.
+fn http_response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    let mut response = response.into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+/// Serves one request per connection on a local port from a background
+/// thread, answering with `respond(request)` where the request is its head
+/// followed by its body. Returns the base URL of the server.
+fn serve<F>(respond: F) -> String
+where
+    F: Fn(&str) -> Vec<u8> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let respond = Arc::new(respond);
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let respond = respond.clone();
+            thread::spawn(move || {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                loop {
+                    let n = stream.read(&mut buf).unwrap_or(0);
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text[..end]
+                            .lines()
+                            .filter_map(|line| line.split_once(':'))
+                            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                            .and_then(|(_, value)| value.trim().parse().ok())
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+                let _ = stream.write_all(&respond(&String::from_utf8_lossy(&request)));
+            });
+        }
+    });
+
+    url
+}
+
+/// A repository that hands out the access token `LOGIN` for the password `pw`
+/// and `REFRESHED` for the refresh token `VALID-RT`, and serves any file to
+/// requests carrying one of them. Also answers as a proxy for other hosts.
+struct StubArmory {
+    url: String,
+    logins: Arc<AtomicUsize>,
+    refreshes: Arc<AtomicUsize>,
+}
+
+impl StubArmory {
+    fn start() -> Self {
+        let (logins, refreshes) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (login_count, refresh_count) = (logins.clone(), refreshes.clone());
+        let url = serve(move |request| {
+            // Requests through a proxy name the whole URL
+            let target = request.split(' ').nth(1).unwrap_or_default();
+            let path = match target.strip_prefix("http://") {
+                Some(rest) => rest.find('/').map_or("/", |slash| &rest[slash..]),
+                None => target,
+            };
+            let token = |access: &str, refresh: &str| {
+                let body = format!(
+                    r#"{{"status": 0, "message": "ok", "field_errors": null, "data": {{"id": 1, "username": "u", "jti": "j", "accessToken": "{}", "refreshToken": "{}"}}}}"#,
+                    access, refresh
+                );
+                http_response("200 OK", &[("Content-Type", "application/json")], body.as_bytes())
+            };
+            let rejected = || http_response("401 Unauthorized", &[], br#"{"status": 401, "message": "unauthorized"}"#);
+
+            if request.starts_with("POST ") && path == "/usercenter/v1/auth/login" {
+                login_count.fetch_add(1, Ordering::SeqCst);
+                if request.contains(r#""password":"pw""#) { token("LOGIN", "VALID-RT") } else { rejected() }
+            } else if request.starts_with("POST ") && path == "/usercenter/v1/auth/refresh" {
+                refresh_count.fetch_add(1, Ordering::SeqCst);
+                if request.contains(r#""refreshToken":"VALID-RT""#) { token("REFRESHED", "VALID-RT") } else { rejected() }
+            } else if request.contains("USER_TOKEN=LOGIN\r\n") || request.contains("USER_TOKEN=REFRESHED\r\n") {
+                http_response("200 OK", &[("Accept-Ranges", "bytes")], b"file contents")
+            } else {
+                rejected()
+            }
+        });
+        StubArmory { url, logins, refreshes }
+    }
+
+    /// Configures the repository in `home` with the credentials `u` / `pw`.
+    fn configure(&self, home: &Path) {
+        let config = format!(r#"{{"repositories": [{{"url": "{}", "username": "u", "password": "pw"}}]}}"#, self.url);
+        std::fs::write(home.join("config.json"), config).unwrap();
+    }
+
+    /// Puts a token pair obtained `age` ago into the token cache in `home`.
+    fn cache_token(&self, home: &Path, access_token: &str, refresh_token: &str, age: Duration) {
+        let issued_at = (SystemTime::now() - age).duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let cache = format!(
+            r#"{{"tokens": {{"{}": {{"access_token": "{}", "refresh_token": "{}", "issued_at": {}}}}}}}"#,
+            self.url, access_token, refresh_token, issued_at
+        );
+        std::fs::write(home.join("tokens.json"), cache).unwrap();
+    }
+}
+
+/// Runs `command` to completion, killing it and failing the test if it takes
+/// longer than `limit`, as it would when waiting for input.
+fn output_within(command: &mut Command, limit: Duration) -> Output {
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    let started = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if started.elapsed() > limit {
+            child.kill().unwrap();
+            panic!("amr still running after {:?}", limit);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    child.wait_with_output().unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn bash_completions_cover_download_options() {
+    let home = scratch_dir("completions");
+    let output = amr(&home).args(["completions", "bash"]).output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let script = String::from_utf8(output.stdout).unwrap();
+    assert!(script.contains("_amr()"), "not a bash completion script");
+    assert!(script.contains("--output"));
+    assert!(script.contains("--no-input"));
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn unwritable_directory_exits_with_io_code() {
+    let home = scratch_dir("exit-io");
+    // Nothing can be created below a regular file, not even by root
+    std::fs::write(home.join("file"), b"").unwrap();
+
+    let output = amr(&home)
+        .arg("-d")
+        .arg(home.join("file").join("downloads"))
+        .arg("http://127.0.0.1:9/a.zip")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(6), "{}", stderr(&output));
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn unauthorized_response_exits_with_auth_code() {
+    let home = scratch_dir("exit-auth");
+    let url = serve(|_| http_response("401 Unauthorized", &[], b"{\"message\": \"login required\"}"));
+
+    let output = amr(&home)
+        .arg("-d")
+        .arg(&home)
+        .arg(format!("{}/files/a.zip", url))
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+    assert!(!home.join("a.zip").exists());
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn no_input_fails_instead_of_prompting_for_credentials() {
+    let home = scratch_dir("no-input");
+
+    // Explicitly, and implied by stdin not being a terminal
+    for args in [&["--no-input"][..], &[]] {
+        let output = output_within(
+            amr(&home)
+                .args(args)
+                .arg("-d")
+                .arg(&home)
+                .arg("https://armory.invalid/files/a.zip")
+                .stdin(Stdio::null()),
+            Duration::from_secs(10),
+        );
+
+        assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+        assert!(stderr(&output).contains("Cannot prompt for input"), "{}", stderr(&output));
+    }
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn revoked_cached_token_is_renewed_once_for_parallel_downloads() {
+    let home = scratch_dir("parallel-renewal");
+    let armory = StubArmory::start();
+    armory.configure(&home);
+    // Fresh enough to be used as is, but unknown to the server
+    armory.cache_token(&home, "REVOKED", "EXPIRED-RT", Duration::ZERO);
+
+    let output = amr(&home)
+        .args(["--jobs", "2", "-d"])
+        .arg(&home)
+        .arg(format!("{}/files/a.bin", armory.url))
+        .arg(format!("{}/files/b.bin", armory.url))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(std::fs::read(home.join("a.bin")).unwrap(), b"file contents");
+    assert_eq!(std::fs::read(home.join("b.bin")).unwrap(), b"file contents");
+    // The cached refresh token is tried first, and is no good either
+    assert_eq!(armory.refreshes.load(Ordering::SeqCst), 1);
+    assert_eq!(armory.logins.load(Ordering::SeqCst), 1);
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn expired_access_token_is_refreshed_without_logging_in() {
+    let home = scratch_dir("refresh");
+    let armory = StubArmory::start();
+    armory.configure(&home);
+    armory.cache_token(&home, "EXPIRED", "VALID-RT", Duration::from_secs(24 * 60 * 60));
+
+    let output = amr(&home).arg("-d").arg(&home).arg(format!("{}/files/a.bin", armory.url)).output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(std::fs::read(home.join("a.bin")).unwrap(), b"file contents");
+    assert_eq!(armory.refreshes.load(Ordering::SeqCst), 1);
+    assert_eq!(armory.logins.load(Ordering::SeqCst), 0);
+    let cache = std::fs::read_to_string(home.join("tokens.json")).unwrap();
+    assert!(cache.contains("REFRESHED"), "{}", cache);
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn expired_refresh_token_falls_back_to_logging_in() {
+    let home = scratch_dir("refresh-expired");
+    let armory = StubArmory::start();
+    armory.configure(&home);
+    armory.cache_token(&home, "EXPIRED", "EXPIRED-RT", Duration::from_secs(24 * 60 * 60));
+
+    let output = amr(&home).arg("-d").arg(&home).arg(format!("{}/files/a.bin", armory.url)).output().unwrap();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(std::fs::read(home.join("a.bin")).unwrap(), b"file contents");
+    assert_eq!(armory.refreshes.load(Ordering::SeqCst), 1);
+    assert_eq!(armory.logins.load(Ordering::SeqCst), 1);
+    let cache = std::fs::read_to_string(home.join("tokens.json")).unwrap();
+    assert!(cache.contains("LOGIN") && !cache.contains("EXPIRED"), "{}", cache);
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn encrypted_config_round_trips() {
+    let home = scratch_dir("encrypt");
+    let armory = StubArmory::start();
+    armory.configure(&home);
+    let config = || std::fs::read_to_string(home.join("config.json")).unwrap();
+
+    let output = amr(&home).args(["config", "encrypt"]).env("AMR_PASSPHRASE", "correct horse").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let encrypted: serde_json::Value = serde_json::from_str(&config()).unwrap();
+    assert_eq!(encrypted["version"], 2);
+    assert_eq!(encrypted["encrypted"], true);
+    assert!(encrypted["repositories"][0]["password"].as_str().unwrap().starts_with("enc:"));
+
+    // Logging in decrypts the password on the fly
+    let output = amr(&home)
+        .args(["--no-token-cache", "-d"])
+        .arg(&home)
+        .arg(format!("{}/files/a.bin", armory.url))
+        .env("AMR_PASSPHRASE", "correct horse")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(armory.logins.load(Ordering::SeqCst), 1);
+
+    let output = amr(&home).args(["config", "decrypt"]).env("AMR_PASSPHRASE", "battery staple").output().unwrap();
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("is the master passphrase correct?"), "{}", stderr(&output));
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&config()).unwrap(), encrypted);
+
+    // The older name of the variable still works
+    let output = amr(&home).args(["config", "decrypt"]).env("AMR_MASTER_PASSPHRASE", "correct horse").output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let decrypted: serde_json::Value = serde_json::from_str(&config()).unwrap();
+    assert_eq!(decrypted["repositories"][0]["password"], "pw");
+    assert!(decrypted.get("version").is_none() && decrypted.get("encrypted").is_none(), "{}", decrypted);
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn global_credentials_are_kept_from_unconfigured_hosts() {
+    let home = scratch_dir("global-credentials");
+    let armory = StubArmory::start();
+    // The stub stands in for a look-alike host, reached through it as a proxy
+    let download = || {
+        amr(&home)
+            .args(["--no-input", "--no-token-cache", "--proxy", &armory.url, "-d"])
+            .arg(&home)
+            .arg("http://armory.attacker.io/files/a.bin")
+            .env("AMR_USERNAME", "u")
+            .env("AMR_PASSWORD", "pw")
+            .output()
+            .unwrap()
+    };
+
+    let output = download();
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(stderr(&output).contains("neither configured nor in allowed_hosts"), "{}", stderr(&output));
+    assert_eq!(armory.logins.load(Ordering::SeqCst), 0);
+
+    let config = r#"{"global": {"allowed_hosts": ["armory.attacker.io"]}, "repositories": []}"#;
+    std::fs::write(home.join("config.json"), config).unwrap();
+    let output = download();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(armory.logins.load(Ordering::SeqCst), 1);
+    assert_eq!(std::fs::read(home.join("a.bin")).unwrap(), b"file contents");
+    std::fs::remove_dir_all(home).unwrap();
+}